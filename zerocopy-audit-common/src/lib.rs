@@ -10,5 +10,23 @@ pub struct LatencyEvent {
     pub t4_tcp_recvmsg: u64,
 }
 
+/// Byte offsets of the pid fields inside the sched tracepoint records.
+/// The loader resolves them from tracefs and hands them to the probe via a map.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct TracepointOffsets {
+    pub sched_wakeup_pid: u32,
+    pub sched_switch_next_pid: u32,
+}
+
+// Fallback layout for 64-bit kernels 4.x through 6.x: an 8-byte common header
+// then comm[16] before `pid`; sched_switch also carries prev_pid, prev_prio
+// and an 8-byte prev_state ahead of next_comm[16] and `next_pid`.
+pub const SCHED_WAKEUP_PID_OFFSET: u32 = 24;
+pub const SCHED_SWITCH_NEXT_PID_OFFSET: u32 = 56;
+
 #[cfg(feature = "user")]
 unsafe impl aya::Pod for LatencyEvent {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for TracepointOffsets {}
@@ -4,11 +4,12 @@
 use aya_ebpf::{
     helpers::{bpf_get_current_pid_tgid, bpf_ktime_get_ns},
     macros::{kprobe, map, tracepoint},
-    maps::{HashMap, PerfEventArray},
+    maps::{Array, HashMap, PerCpuArray, PerfEventArray},
     programs::{ProbeContext, TracePointContext},
-    EbpfContext,
 };
-use zerocopy_audit_common::LatencyEvent;
+use zerocopy_audit_common::{
+    LatencyEvent, TracepointOffsets, SCHED_SWITCH_NEXT_PID_OFFSET, SCHED_WAKEUP_PID_OFFSET,
+};
 
 #[map]
 static TARGET_PID: HashMap<u32, u32> = HashMap::with_max_entries(1, 0);
@@ -19,23 +20,101 @@ static EVENTS: PerfEventArray<LatencyEvent> = PerfEventArray::new(0);
 #[map]
 static START_TIMES: HashMap<u32, LatencyEvent> = HashMap::with_max_entries(1024, 0);
 
+#[map]
+static TRACEPOINT_OFFSETS: Array<TracepointOffsets> = Array::with_max_entries(1, 0);
+
+// Pid reads that fail or land outside the pid range, i.e. the offsets don't match
+// this kernel.
+#[map]
+static IMPLAUSIBLE_PIDS: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+
+// PID_MAX_LIMIT on 64-bit kernels.
+const PID_MAX_LIMIT: u32 = 4_194_304;
+
+// net_rx runs in softirq context, so the current pid is whichever task the
+// packet interrupted, not the socket owner. Rather than walking
+// skb -> sock -> pid, we lean on the receive path waking the blocked reader
+// from that same softirq: when sched_waking fires for the target, we take the
+// last packet received on the waking CPU as the one that made it runnable.
+// This is a heuristic and t1 is approximate. With GRO or list receive,
+// netif_receive_skb fires for the whole batch before any packet reaches TCP,
+// so t1 is the batch's last packet, which need not be the target's. On a busy
+// host any packet inside NET_RX_CORRELATION_WINDOW_NS is attributed, even to
+// wakeups that weren't caused by the network (timers, futexes, pipes).
+// sched_wakeup is no good for this: on the TTWU_QUEUE wakelist path (target
+// CPU idle or in another LLC) it runs on the target CPU from
+// sched_ttwu_pending, whereas sched_waking always runs in the waker's context.
+#[map]
+static LAST_NET_RX: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+
+// t1 captured at sched_waking, handed to sched_wakeup for the same pid.
+#[map]
+static WAKING_NET_RX: HashMap<u32, u64> = HashMap::with_max_entries(1024, 0);
+
+// Packets older than this are treated as unrelated to the wakeup.
+const NET_RX_CORRELATION_WINDOW_NS: u64 = 1_000_000;
+
 #[tracepoint]
 pub fn audit_net_rx(_ctx: TracePointContext) -> u32 {
-    let _pid = bpf_get_current_pid_tgid() as u32;
-    let _time = unsafe { bpf_ktime_get_ns() };
+    if let Some(last_rx) = LAST_NET_RX.get_ptr_mut(0) {
+        unsafe { *last_rx = bpf_ktime_get_ns() };
+    }
+    0
+}
+
+// The loader fills TRACEPOINT_OFFSETS before attaching; a zero entry means it
+// never did, so fall back to the compiled-in layout.
+#[inline(always)]
+fn read_pid(ctx: &TracePointContext, offset: u32, fallback: u32) -> Option<u32> {
+    let offset = if offset == 0 { fallback } else { offset };
+    // A failed read and an out-of-range pid both mean the offset is wrong, so
+    // count both; pid_max itself is exclusive.
+    match unsafe { ctx.read_at::<u32>(offset as usize) } {
+        Ok(pid) if pid < PID_MAX_LIMIT => Some(pid),
+        _ => {
+            if let Some(count) = IMPLAUSIBLE_PIDS.get_ptr_mut(0) {
+                unsafe { *count += 1 };
+            }
+            None
+        }
+    }
+}
+
+// sched_waking shares the sched_wakeup_template record layout, so the same
+// pid offset applies.
+#[tracepoint]
+pub fn audit_sched_waking(ctx: TracePointContext) -> u32 {
+    let offset = TRACEPOINT_OFFSETS.get(0).map_or(0, |o| o.sched_wakeup_pid);
+    let Some(pid) = read_pid(&ctx, offset, SCHED_WAKEUP_PID_OFFSET) else {
+        return 0;
+    };
+
+    if unsafe { TARGET_PID.get(&pid).is_some() } {
+        let time = unsafe { bpf_ktime_get_ns() };
+        let t1_net_rx = match LAST_NET_RX.get(0) {
+            Some(&rx) if rx != 0 && time.saturating_sub(rx) < NET_RX_CORRELATION_WINDOW_NS => rx,
+            _ => 0,
+        };
+        // Always overwrite so an uncorrelated wake never inherits a stale t1.
+        let _ = WAKING_NET_RX.insert(&pid, &t1_net_rx, 0);
+    }
     0
 }
 
 #[tracepoint]
 pub fn audit_sched_wakeup(ctx: TracePointContext) -> u32 {
-    let pid =
-        unsafe { core::ptr::read_unaligned((ctx.as_ptr() as *const u8).add(16) as *const u32) };
+    let offset = TRACEPOINT_OFFSETS.get(0).map_or(0, |o| o.sched_wakeup_pid);
+    let Some(pid) = read_pid(&ctx, offset, SCHED_WAKEUP_PID_OFFSET) else {
+        return 0;
+    };
 
     if unsafe { TARGET_PID.get(&pid).is_some() } {
         let time = unsafe { bpf_ktime_get_ns() };
+        let t1_net_rx = unsafe { WAKING_NET_RX.get(&pid) }.copied().unwrap_or(0);
+        let _ = WAKING_NET_RX.remove(&pid);
         let event = LatencyEvent {
             pid,
-            t1_net_rx: 0,
+            t1_net_rx,
             t2_sched_wakeup: time,
             t3_sched_switch: 0,
             t4_tcp_recvmsg: 0,
@@ -47,8 +126,12 @@ pub fn audit_sched_wakeup(ctx: TracePointContext) -> u32 {
 
 #[tracepoint]
 pub fn audit_sched_switch(ctx: TracePointContext) -> u32 {
-    let next_pid =
-        unsafe { core::ptr::read_unaligned((ctx.as_ptr() as *const u8).add(40) as *const u32) };
+    let offset = TRACEPOINT_OFFSETS
+        .get(0)
+        .map_or(0, |o| o.sched_switch_next_pid);
+    let Some(next_pid) = read_pid(&ctx, offset, SCHED_SWITCH_NEXT_PID_OFFSET) else {
+        return 0;
+    };
 
     if unsafe { TARGET_PID.get(&next_pid).is_some() } {
         if let Some(mut event) = unsafe { START_TIMES.get(&next_pid) }.copied() {
@@ -1,11 +1,11 @@
+mod report;
+
 use clap::Parser;
-use log::info;
-use serde::Serialize;
-use tokio::signal;
-use zerocopy_audit_common::LatencyEvent;
+use log::{info, warn};
+use report::{BillOfHealth, EventDelays, LatencySamples, MarketCalendar, BILL_OF_HEALTH_PATH};
 
 #[cfg(target_os = "linux")]
-use aya::maps::{perf::AsyncPerfEventArray, HashMap};
+use aya::maps::{perf::AsyncPerfEventArray, Array, HashMap, PerCpuArray};
 #[cfg(target_os = "linux")]
 use aya::programs::{KProbe, TracePoint};
 #[cfg(target_os = "linux")]
@@ -14,26 +14,82 @@ use aya::util::online_cpus;
 use aya::{include_bytes_aligned, Ebpf};
 #[cfg(target_os = "linux")]
 use bytes::BytesMut;
+#[cfg(target_os = "linux")]
+use std::sync::{Arc, Mutex};
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+#[cfg(target_os = "linux")]
+use tokio::signal;
+#[cfg(target_os = "linux")]
+use tokio::sync::Notify;
+#[cfg(target_os = "linux")]
+use tokio::task::JoinSet;
+#[cfg(target_os = "linux")]
+use zerocopy_audit_common::{
+    LatencyEvent, TracepointOffsets, SCHED_SWITCH_NEXT_PID_OFFSET, SCHED_WAKEUP_PID_OFFSET,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Sovereign Audit: eBPF diagnostic wedge for Jitter Tax", long_about = None)]
 struct Args {
     #[arg(short, long)]
     pid: u32,
+    /// Daily notional volume traded by the target, in USD
     #[arg(short, long, default_value_t = 50_000_000.0)]
     volume: f64,
-    #[arg(short, long, default_value_t = 0.0001)] // 1 BPS
+    /// Fraction of daily notional lost per second of p99 latency (0.0001 = 1 bps per second)
+    #[arg(short, long, default_value_t = 0.0001)]
     slippage: f64,
+    /// Trading calendar for annualizing the loss: crypto (365), us-equities (252), forex (260) or custom:N for N days (a bare N also works)
+    #[arg(long, default_value = "crypto")]
+    calendar: MarketCalendar,
+    /// Suppress the Jitter Tax call-to-action banner on shutdown
+    #[arg(long)]
+    no_cta: bool,
+    /// Stop and emit the Bill of Health after this many valid events
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..))]
+    samples: u64,
+    /// Stop after this many seconds even if fewer samples were collected
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    duration: Option<u64>,
+}
+
+#[cfg(target_os = "linux")]
+const TRACEFS_ROOTS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+/// Reads a field's byte offset from the tracepoint's tracefs `format` file.
+#[cfg(target_os = "linux")]
+fn tracepoint_field_offset(category: &str, name: &str, field: &str) -> Option<u32> {
+    TRACEFS_ROOTS.iter().find_map(|root| {
+        let format =
+            std::fs::read_to_string(format!("{root}/events/{category}/{name}/format")).ok()?;
+        field_offset(&format, field)
+    })
+}
+
+/// Finds a field's byte offset in tracefs `format` text, matching the field
+/// name exactly, e.g. `field:pid_t pid;\toffset:24;\tsize:4;\tsigned:1;`.
+// Only the Linux entry point reads tracefs, but keep it compiled everywhere.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn field_offset(format: &str, field: &str) -> Option<u32> {
+    format.lines().find_map(|line| {
+        let mut attrs = line.split(';').map(str::trim);
+        let decl = attrs.next()?.strip_prefix("field:")?;
+        if decl.rsplit(' ').next()? != field {
+            return None;
+        }
+        attrs.find_map(|a| a.strip_prefix("offset:"))?.parse().ok()
+    })
 }
 
-#[derive(Serialize)]
-#[allow(dead_code)]
-struct BillOfHealth {
-    target_pid: u32,
-    p99_sched_wakeup_ns: u64,
-    p99_kernel_stack_ns: u64,
-    p99_total_overhead_ns: u64,
-    jitter_tax_annual_loss: f64,
+#[cfg(target_os = "linux")]
+fn resolve_offset(category: &str, name: &str, field: &str, fallback: u32) -> u32 {
+    tracepoint_field_offset(category, name, field).unwrap_or_else(|| {
+        warn!(
+            "Could not read {category}:{name} format from tracefs; assuming {field} at offset {fallback}."
+        );
+        fallback
+    })
 }
 
 #[cfg(target_os = "linux")]
@@ -52,6 +108,31 @@ async fn main() -> anyhow::Result<()> {
 
     let mut bpf = Ebpf::load(bpf_data)?;
 
+    // Resolve tracepoint field offsets before any program can fire
+    let offsets = TracepointOffsets {
+        sched_wakeup_pid: resolve_offset("sched", "sched_wakeup", "pid", SCHED_WAKEUP_PID_OFFSET),
+        sched_switch_next_pid: resolve_offset(
+            "sched",
+            "sched_switch",
+            "next_pid",
+            SCHED_SWITCH_NEXT_PID_OFFSET,
+        ),
+    };
+    let mut offsets_map: Array<_, TracepointOffsets> =
+        Array::try_from(bpf.map_mut("TRACEPOINT_OFFSETS").unwrap())?;
+    offsets_map.set(0, offsets, 0)?;
+
+    // Attach Net RX
+    let net_rx: &mut TracePoint = bpf.program_mut("audit_net_rx").unwrap().try_into()?;
+    net_rx.load()?;
+    net_rx.attach("net", "netif_receive_skb")?;
+
+    // Attach Sched Waking
+    let sched_waking: &mut TracePoint =
+        bpf.program_mut("audit_sched_waking").unwrap().try_into()?;
+    sched_waking.load()?;
+    sched_waking.attach("sched", "sched_waking")?;
+
     // Attach Sched Wakeup
     let sched_wakeup: &mut TracePoint =
         bpf.program_mut("audit_sched_wakeup").unwrap().try_into()?;
@@ -79,15 +160,22 @@ async fn main() -> anyhow::Result<()> {
     // Setup Ring Buffer Polling
     let mut events: AsyncPerfEventArray<_> = bpf.take_map("EVENTS").unwrap().try_into()?;
 
-    let _runqueue_delays: Vec<u64> = Vec::new();
-    let _stack_delays: Vec<u64> = Vec::new();
+    let samples = Arc::new(Mutex::new(LatencySamples::default()));
+    let baseline_reached = Arc::new(Notify::new());
 
-    info!("Listening for 100 packets to establish the baseline...");
+    info!(
+        "Listening for {} packets to establish the baseline...",
+        args.samples
+    );
 
+    let mut readers = JoinSet::new();
     for cpu_id in online_cpus().map_err(|e| anyhow::anyhow!("CPU Error: {:?}", e))? {
         let mut buf = events.open(cpu_id, None)?;
+        let samples = Arc::clone(&samples);
+        let baseline_reached = Arc::clone(&baseline_reached);
+        let target_samples = args.samples;
 
-        tokio::spawn(async move {
+        readers.spawn(async move {
             let mut buffers = (0..10)
                 .map(|_| BytesMut::with_capacity(1024))
                 .collect::<Vec<_>>();
@@ -98,13 +186,20 @@ async fn main() -> anyhow::Result<()> {
                             std::ptr::read_unaligned(buf.as_ptr() as *const LatencyEvent)
                         };
                         // Aggregation calculations
-                        let rq_delay = event.t3_sched_switch.saturating_sub(event.t2_sched_wakeup);
-                        let _stack_delay =
-                            event.t4_tcp_recvmsg.saturating_sub(event.t2_sched_wakeup);
-                        if rq_delay > 0 && rq_delay < 10_000_000 {
+                        if let Some(delays) = EventDelays::from_event(&event) {
                             // Print the terrifying reality
-                            println!("🚨 [PID {}] Woke up at {}ns, Executed at {}ns. RunQueue Wait: {}µs", 
-                                event.pid, event.t2_sched_wakeup, event.t3_sched_switch, rq_delay / 1000);
+                            match delays.net_rx {
+                                Some(net_rx) => println!("🚨 [PID {}] Packet at {}ns, Woke up at {}ns, Executed at {}ns. NetRx->Wakeup: {}µs, RunQueue Wait: {}µs",
+                                    event.pid, event.t1_net_rx, event.t2_sched_wakeup, event.t3_sched_switch, net_rx / 1000, delays.runqueue / 1000),
+                                None => println!("🚨 [PID {}] Woke up at {}ns, Executed at {}ns. NetRx->Wakeup: n/a, RunQueue Wait: {}µs",
+                                    event.pid, event.t2_sched_wakeup, event.t3_sched_switch, delays.runqueue / 1000),
+                            }
+
+                            let mut samples = samples.lock().unwrap();
+                            samples.push(&delays);
+                            if samples.len() as u64 == target_samples {
+                                baseline_reached.notify_one();
+                            }
                         }
                     }
                 }
@@ -112,24 +207,174 @@ async fn main() -> anyhow::Result<()> {
         });
     }
 
-    info!("Waiting for Ctrl-C...");
-    signal::ctrl_c().await?;
+    let deadline = async {
+        match args.duration {
+            Some(secs) => tokio::time::sleep(Duration::from_secs(secs)).await,
+            None => std::future::pending().await,
+        }
+    };
+
+    info!("Waiting for baseline, timeout or Ctrl-C...");
+    tokio::select! {
+        _ = baseline_reached.notified() => info!("Baseline of {} samples collected.", args.samples),
+        _ = deadline => info!("Collection window of {}s elapsed.", args.duration.unwrap_or_default()),
+        res = signal::ctrl_c() => res?,
+    }
     info!("Detaching probes and shutting down.");
 
-    println!("\n=======================================================");
-    println!("🚨 JITTER TAX NOTIFICATION");
-    println!("Your p99 latency indicates a high probability of structural Alpha Bleed.");
-    println!(
-        "To receive a specialized architectural remedy roadmap, upload your Bill of Health to:"
+    // Read the counters before the maps go away with the Ebpf handle.
+    let implausible_pids: PerCpuArray<_, u64> =
+        PerCpuArray::try_from(bpf.map("IMPLAUSIBLE_PIDS").unwrap())?;
+    let implausible_reads: u64 = implausible_pids.get(&0, 0)?.iter().sum();
+    if implausible_reads > 0 {
+        warn!(
+            "{} sched tracepoint pid reads failed or were out of range; field offsets may not match this kernel.",
+            implausible_reads
+        );
+    }
+
+    // Stop the per-CPU readers, then drop the Ebpf handle to detach every probe,
+    // so nothing is still tracing the target while the report is produced.
+    readers.shutdown().await;
+    drop(bpf);
+
+    let mut samples = std::mem::take(&mut *samples.lock().unwrap());
+    if samples.is_empty() {
+        warn!(
+            "No events captured for PID {}; Bill of Health will report zero latency.",
+            args.pid
+        );
+    }
+    let report = BillOfHealth::from_samples(
+        args.pid,
+        &mut samples,
+        args.volume,
+        args.slippage,
+        args.calendar,
     );
-    println!("👉 https://zerocopy.systems/audit?utm_source=github&utm_medium=oss_cli&utm_campaign=jitter_tax");
-    println!("=======================================================\n");
+    report.write(BILL_OF_HEALTH_PATH)?;
+    info!(
+        "Bill of Health written to {} ({} samples, p99 total overhead {}ns, Jitter Tax ${:.2}/yr over {} trading days [{}])",
+        BILL_OF_HEALTH_PATH,
+        report.sample_count,
+        report.p99_total_overhead_ns,
+        report.jitter_tax_annual_loss,
+        report.trading_days,
+        report.calendar
+    );
+
+    if !args.no_cta {
+        println!("\n=======================================================");
+        println!("🚨 JITTER TAX NOTIFICATION");
+        println!("Your p99 latency indicates a high probability of structural Alpha Bleed.");
+        println!(
+            "To receive a specialized architectural remedy roadmap, upload your Bill of Health to:"
+        );
+        println!("👉 https://zerocopy.systems/audit?utm_source=github&utm_medium=oss_cli&utm_campaign=jitter_tax");
+        println!("=======================================================\n");
+    }
 
     Ok(())
 }
 
 #[cfg(not(target_os = "linux"))]
 fn main() -> anyhow::Result<()> {
-    println!("❌ Sovereign Audit is a native eBPF probe and must be compiled and executed on a Linux environment.");
+    env_logger::init();
+    let args = Args::parse();
+    println!("⚠️  SIMULATED: Sovereign Audit is a native eBPF probe and can only attach on Linux.");
+    println!(
+        "⚠️  SIMULATED: Generating a Bill of Health for PID {} from {} synthetic events.",
+        args.pid, args.samples
+    );
+
+    let mut samples = LatencySamples::default();
+    for event in report::simulated_events(args.pid, args.samples) {
+        if let Some(delays) = EventDelays::from_event(&event) {
+            samples.push(&delays);
+        }
+    }
+    if samples.is_empty() {
+        warn!("No simulated events survived filtering; Bill of Health will report zero latency.");
+    }
+
+    let mut report = BillOfHealth::from_samples(
+        args.pid,
+        &mut samples,
+        args.volume,
+        args.slippage,
+        args.calendar,
+    );
+    report.simulated = true;
+    report.write(BILL_OF_HEALTH_PATH)?;
+    info!(
+        "SIMULATED Bill of Health written to {} ({} samples, p99 total overhead {}ns, Jitter Tax ${:.2}/yr over {} trading days [{}])",
+        BILL_OF_HEALTH_PATH,
+        report.sample_count,
+        report.p99_total_overhead_ns,
+        report.jitter_tax_annual_loss,
+        report.trading_days,
+        report.calendar
+    );
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::field_offset;
+
+    const SCHED_WAKEUP_FORMAT: &str = "name: sched_wakeup
+ID: 316
+format:
+\tfield:unsigned short common_type;\toffset:0;\tsize:2;\tsigned:0;
+\tfield:unsigned char common_flags;\toffset:2;\tsize:1;\tsigned:0;
+\tfield:unsigned char common_preempt_count;\toffset:3;\tsize:1;\tsigned:0;
+\tfield:int common_pid;\toffset:4;\tsize:4;\tsigned:1;
+
+\tfield:char comm[16];\toffset:8;\tsize:16;\tsigned:0;
+\tfield:pid_t pid;\toffset:24;\tsize:4;\tsigned:1;
+\tfield:int prio;\toffset:28;\tsize:4;\tsigned:1;
+\tfield:int target_cpu;\toffset:32;\tsize:4;\tsigned:1;
+
+print fmt: \"comm=%s pid=%d prio=%d target_cpu=%03d\", REC->comm, REC->pid, REC->prio, REC->target_cpu
+";
+
+    const SCHED_SWITCH_FORMAT: &str = "name: sched_switch
+ID: 314
+format:
+\tfield:unsigned short common_type;\toffset:0;\tsize:2;\tsigned:0;
+\tfield:unsigned char common_flags;\toffset:2;\tsize:1;\tsigned:0;
+\tfield:unsigned char common_preempt_count;\toffset:3;\tsize:1;\tsigned:0;
+\tfield:int common_pid;\toffset:4;\tsize:4;\tsigned:1;
+
+\tfield:char prev_comm[16];\toffset:8;\tsize:16;\tsigned:0;
+\tfield:pid_t prev_pid;\toffset:24;\tsize:4;\tsigned:1;
+\tfield:int prev_prio;\toffset:28;\tsize:4;\tsigned:1;
+\tfield:long prev_state;\toffset:32;\tsize:8;\tsigned:1;
+\tfield:char next_comm[16];\toffset:40;\tsize:16;\tsigned:0;
+\tfield:pid_t next_pid;\toffset:56;\tsize:4;\tsigned:1;
+\tfield:int next_prio;\toffset:60;\tsize:4;\tsigned:1;
+
+print fmt: \"prev_comm=%s prev_pid=%d prev_prio=%d prev_state=%s%s ==> next_comm=%s next_pid=%d next_prio=%d\", REC->prev_comm, REC->prev_pid, REC->prev_prio, REC->prev_state, REC->next_comm, REC->next_pid, REC->next_prio
+";
+
+    #[test]
+    fn finds_sched_wakeup_pid() {
+        assert_eq!(field_offset(SCHED_WAKEUP_FORMAT, "pid"), Some(24));
+        assert_eq!(field_offset(SCHED_WAKEUP_FORMAT, "common_pid"), Some(4));
+    }
+
+    #[test]
+    fn finds_sched_switch_next_pid() {
+        assert_eq!(field_offset(SCHED_SWITCH_FORMAT, "next_pid"), Some(56));
+        assert_eq!(field_offset(SCHED_SWITCH_FORMAT, "prev_pid"), Some(24));
+    }
+
+    #[test]
+    fn does_not_match_on_suffix() {
+        // `pid` must not resolve to common_pid, prev_pid or next_pid.
+        assert_eq!(field_offset(SCHED_SWITCH_FORMAT, "pid"), None);
+        assert_eq!(field_offset(SCHED_WAKEUP_FORMAT, "next_pid"), None);
+        assert_eq!(field_offset("", "pid"), None);
+    }
+}
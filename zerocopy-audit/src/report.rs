@@ -0,0 +1,384 @@
+//! Platform-independent aggregation of probe events into a Bill of Health.
+//!
+//! Nothing in here touches eBPF, so the statistics and Jitter Tax math can be
+//! developed and exercised off a Linux box with synthetic `LatencyEvent`s.
+
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+use zerocopy_audit_common::LatencyEvent;
+
+pub const BILL_OF_HEALTH_PATH: &str = "bill_of_health.json";
+
+/// Trading calendar used to annualize the daily Jitter Tax.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MarketCalendar {
+    /// Crypto markets never close.
+    Crypto247,
+    UsEquities,
+    Forex,
+    Custom(u32),
+}
+
+impl MarketCalendar {
+    pub fn trading_days(self) -> u32 {
+        match self {
+            MarketCalendar::Crypto247 => 365,
+            MarketCalendar::UsEquities => 252,
+            MarketCalendar::Forex => 260,
+            MarketCalendar::Custom(days) => days,
+        }
+    }
+}
+
+impl FromStr for MarketCalendar {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "crypto" => Ok(MarketCalendar::Crypto247),
+            "us-equities" => Ok(MarketCalendar::UsEquities),
+            "forex" => Ok(MarketCalendar::Forex),
+            other => match other.strip_prefix("custom:").unwrap_or(other).parse() {
+                Ok(days @ 1..=366) => Ok(MarketCalendar::Custom(days)),
+                _ => Err(format!(
+                    "expected crypto, us-equities, forex or custom:N with N in 1..=366, got '{other}'"
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for MarketCalendar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MarketCalendar::Crypto247 => write!(f, "crypto"),
+            MarketCalendar::UsEquities => write!(f, "us-equities"),
+            MarketCalendar::Forex => write!(f, "forex"),
+            MarketCalendar::Custom(days) => write!(f, "custom:{days}"),
+        }
+    }
+}
+
+/// Latency breakdown of a single wakeup, in nanoseconds.
+pub struct EventDelays {
+    /// Packet arrival to wakeup; `None` when no packet could be correlated.
+    pub net_rx: Option<u64>,
+    pub runqueue: u64,
+    pub stack: u64,
+    pub total: u64,
+}
+
+impl EventDelays {
+    /// Returns `None` for events whose target was never switched in after the
+    /// wakeup. Long runqueue waits are kept: preemption and starvation between
+    /// wakeup and recvmsg are exactly the tail this audit reports.
+    pub fn from_event(event: &LatencyEvent) -> Option<Self> {
+        if event.t3_sched_switch == 0 || event.t3_sched_switch < event.t2_sched_wakeup {
+            return None;
+        }
+        let runqueue = event.t3_sched_switch - event.t2_sched_wakeup;
+
+        // t1 is 0 when no packet on the waking CPU could be correlated with
+        // this wakeup. Total always spans wakeup to recvmsg so every sample
+        // measures the same thing; the net-rx leg is reported separately.
+        let net_rx =
+            (event.t1_net_rx != 0).then(|| event.t2_sched_wakeup.saturating_sub(event.t1_net_rx));
+
+        Some(EventDelays {
+            net_rx,
+            runqueue,
+            stack: event.t4_tcp_recvmsg.saturating_sub(event.t3_sched_switch),
+            total: event.t4_tcp_recvmsg.saturating_sub(event.t2_sched_wakeup),
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct LatencySamples {
+    net_rx: Vec<u64>,
+    runqueue: Vec<u64>,
+    stack: Vec<u64>,
+    total: Vec<u64>,
+}
+
+impl LatencySamples {
+    pub fn push(&mut self, delays: &EventDelays) {
+        if let Some(net_rx) = delays.net_rx {
+            self.net_rx.push(net_rx);
+        }
+        self.runqueue.push(delays.runqueue);
+        self.stack.push(delays.stack);
+        self.total.push(delays.total);
+    }
+
+    pub fn len(&self) -> usize {
+        self.total.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total.is_empty()
+    }
+}
+
+/// Nearest-rank percentile over an unsorted sample set. Returns 0 when empty.
+fn percentile(samples: &mut [u64], pct: f64) -> u64 {
+    if samples.is_empty() {
+        return 0;
+    }
+    samples.sort_unstable();
+    let rank = ((pct / 100.0) * samples.len() as f64).ceil() as usize;
+    samples[rank.clamp(1, samples.len()) - 1]
+}
+
+#[derive(Serialize)]
+pub struct BillOfHealth {
+    pub target_pid: u32,
+    pub simulated: bool,
+    pub sample_count: usize,
+    pub net_rx_correlated_count: usize,
+    pub p99_net_rx_ns: u64,
+    pub p99_sched_wakeup_ns: u64,
+    pub p99_kernel_stack_ns: u64,
+    pub p99_total_overhead_ns: u64,
+    pub jitter_tax_annual_loss: f64,
+    pub calendar: String,
+    pub trading_days: u32,
+}
+
+impl BillOfHealth {
+    pub fn from_samples(
+        target_pid: u32,
+        samples: &mut LatencySamples,
+        volume: f64,
+        slippage: f64,
+        calendar: MarketCalendar,
+    ) -> Self {
+        let p99_total_overhead_ns = percentile(&mut samples.total, 99.0);
+        // L = V x F x P(J): every second of tail latency on the daily volume
+        // costs `slippage` of that notional, compounded over the trading year.
+        let trading_days = calendar.trading_days();
+        let jitter_tax_annual_loss =
+            volume * slippage * (p99_total_overhead_ns as f64 / 1e9) * trading_days as f64;
+
+        BillOfHealth {
+            target_pid,
+            simulated: false,
+            sample_count: samples.len(),
+            net_rx_correlated_count: samples.net_rx.len(),
+            p99_net_rx_ns: percentile(&mut samples.net_rx, 99.0),
+            p99_sched_wakeup_ns: percentile(&mut samples.runqueue, 99.0),
+            p99_kernel_stack_ns: percentile(&mut samples.stack, 99.0),
+            p99_total_overhead_ns,
+            jitter_tax_annual_loss,
+            calendar: calendar.to_string(),
+            trading_days,
+        }
+    }
+
+    pub fn write(&self, path: &str) -> std::io::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Deterministic synthetic events shaped like a lightly loaded host: tens of
+/// microseconds per stage with a ~1% tail of millisecond runqueue stalls.
+/// Events are generated lazily so `count` can be arbitrarily large.
+// Only the non-Linux entry point simulates, but keep it compiled everywhere.
+#[cfg_attr(target_os = "linux", allow(dead_code))]
+pub fn simulated_events(pid: u32, count: u64) -> impl Iterator<Item = LatencyEvent> {
+    // xorshift64, fixed seed so every simulated report is reproducible
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut next = move |range: u64| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state % range
+    };
+
+    (0..count).map(move |i| {
+        let t1_net_rx = 1_000_000_000 + i * 1_000_000;
+        let t2_sched_wakeup = t1_net_rx + 5_000 + next(20_000);
+        let runqueue = if next(100) == 0 {
+            1_000_000 + next(1_000_000)
+        } else {
+            2_000 + next(78_000)
+        };
+        let t3_sched_switch = t2_sched_wakeup + runqueue;
+        LatencyEvent {
+            pid,
+            t1_net_rx,
+            t2_sched_wakeup,
+            t3_sched_switch,
+            t4_tcp_recvmsg: t3_sched_switch + 10_000 + next(30_000),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(t1: u64, t2: u64, t3: u64, t4: u64) -> LatencyEvent {
+        LatencyEvent {
+            pid: 42,
+            t1_net_rx: t1,
+            t2_sched_wakeup: t2,
+            t3_sched_switch: t3,
+            t4_tcp_recvmsg: t4,
+        }
+    }
+
+    #[test]
+    fn percentile_of_empty_is_zero() {
+        assert_eq!(percentile(&mut [], 99.0), 0);
+    }
+
+    #[test]
+    fn percentile_of_single_sample_is_that_sample() {
+        assert_eq!(percentile(&mut [7], 0.0), 7);
+        assert_eq!(percentile(&mut [7], 99.0), 7);
+    }
+
+    #[test]
+    fn percentile_uses_nearest_rank() {
+        let mut samples: Vec<u64> = (1..=100).rev().collect();
+        assert_eq!(percentile(&mut samples, 50.0), 50);
+        assert_eq!(percentile(&mut samples, 99.0), 99);
+        assert_eq!(percentile(&mut samples, 99.5), 100);
+        assert_eq!(percentile(&mut samples, 100.0), 100);
+    }
+
+    #[test]
+    fn named_calendars_parse() {
+        let cases = [
+            ("crypto", MarketCalendar::Crypto247, 365),
+            ("us-equities", MarketCalendar::UsEquities, 252),
+            ("forex", MarketCalendar::Forex, 260),
+        ];
+        for (name, calendar, days) in cases {
+            assert_eq!(name.parse::<MarketCalendar>(), Ok(calendar));
+            assert_eq!(calendar.trading_days(), days);
+        }
+    }
+
+    #[test]
+    fn custom_calendar_accepts_bare_and_prefixed_counts() {
+        assert_eq!("252".parse(), Ok(MarketCalendar::Custom(252)));
+        assert_eq!("custom:252".parse(), Ok(MarketCalendar::Custom(252)));
+        assert_eq!(MarketCalendar::Custom(252).trading_days(), 252);
+    }
+
+    #[test]
+    fn calendar_rejects_out_of_range_and_unknown() {
+        for input in ["0", "367", "foo"] {
+            assert!(input.parse::<MarketCalendar>().is_err(), "{input}");
+        }
+    }
+
+    #[test]
+    fn calendar_display_round_trips() {
+        let calendars = [
+            MarketCalendar::Crypto247,
+            MarketCalendar::UsEquities,
+            MarketCalendar::Forex,
+            MarketCalendar::Custom(300),
+        ];
+        for calendar in calendars {
+            assert_eq!(calendar.to_string().parse(), Ok(calendar));
+        }
+    }
+
+    #[test]
+    fn from_event_drops_unswitched_events() {
+        assert!(EventDelays::from_event(&event(900, 1_000, 0, 5_000)).is_none());
+        assert!(EventDelays::from_event(&event(900, 1_000, 999, 5_000)).is_none());
+    }
+
+    #[test]
+    fn from_event_keeps_long_runqueue_waits() {
+        // A target preempted for 50ms between wakeup and recvmsg is real jitter.
+        let starved = EventDelays::from_event(&event(900, 1_000, 50_001_000, 50_005_000)).unwrap();
+        assert_eq!(starved.runqueue, 50_000_000);
+        assert_eq!(starved.total, 50_004_000);
+    }
+
+    #[test]
+    fn from_event_splits_stages() {
+        let delays = EventDelays::from_event(&event(900, 1_000, 3_000, 7_000)).unwrap();
+        assert_eq!(delays.net_rx, Some(100));
+        assert_eq!(delays.runqueue, 2_000);
+        assert_eq!(delays.stack, 4_000);
+        assert_eq!(delays.total, 6_000);
+    }
+
+    #[test]
+    fn from_event_without_packet_measures_from_wakeup() {
+        let delays = EventDelays::from_event(&event(0, 1_000, 3_000, 7_000)).unwrap();
+        assert_eq!(delays.net_rx, None);
+        assert_eq!(delays.total, 6_000);
+
+        let mut samples = LatencySamples::default();
+        samples.push(&delays);
+        assert_eq!(samples.len(), 1);
+        assert!(samples.net_rx.is_empty());
+    }
+
+    fn simulated_report() -> BillOfHealth {
+        let mut samples = LatencySamples::default();
+        for delays in
+            simulated_events(42, 1_000).filter_map(|event| EventDelays::from_event(&event))
+        {
+            samples.push(&delays);
+        }
+        BillOfHealth::from_samples(
+            42,
+            &mut samples,
+            1_000_000.0,
+            0.0001,
+            MarketCalendar::Crypto247,
+        )
+    }
+
+    #[test]
+    fn simulated_report_is_pinned() {
+        let report = simulated_report();
+        assert_eq!(report.sample_count, 1_000);
+        assert_eq!(report.net_rx_correlated_count, 1_000);
+        assert_eq!(report.p99_total_overhead_ns, 115_680);
+        // 1e6 USD x 1e-4 per second x 115.68us x 365 days
+        assert!((report.jitter_tax_annual_loss - 4.22232).abs() < 1e-9);
+        assert_eq!(report.trading_days, 365);
+    }
+
+    #[test]
+    fn bill_of_health_json_keys_are_stable() {
+        let mut report = simulated_report();
+        report.simulated = true;
+        let json = serde_json::to_value(&report).unwrap();
+        let keys: Vec<&str> = json
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        let mut expected = vec![
+            "target_pid",
+            "simulated",
+            "sample_count",
+            "net_rx_correlated_count",
+            "p99_net_rx_ns",
+            "p99_sched_wakeup_ns",
+            "p99_kernel_stack_ns",
+            "p99_total_overhead_ns",
+            "jitter_tax_annual_loss",
+            "calendar",
+            "trading_days",
+        ];
+        expected.sort_unstable();
+        assert_eq!(keys, expected);
+        assert_eq!(json["simulated"], true);
+        assert_eq!(json["sample_count"], 1_000);
+        assert_eq!(json["calendar"], "crypto");
+    }
+}